@@ -1,6 +1,7 @@
 use thiserror::Error;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 
 enum BasicError {
     UnsupportedConfigVersion,
@@ -12,6 +13,7 @@ enum BasicError {
     #[allow(dead_code)]
     InvalidPathEncoding,
     NonUnicodeTag,
+    DanglingContinuation,
 }
 
 #[derive(Clone, Debug, Error)]
@@ -32,6 +34,12 @@ pub enum TextError {
     InvalidPathEncoding(usize),
     #[error("non-unicode tag on line {0}")]
     NonUnicodeTag(usize),
+    #[error("continuation line has no preceding rule or option to fold into on line {0}")]
+    DanglingContinuation(usize),
+    #[error("include cycle detected including {1:?} from line {0}")]
+    IncludeCycle(usize, PathBuf),
+    #[error("failed to read included file {1:?} from line {0}: {2}")]
+    IncludeNotFound(usize, PathBuf, String),
 }
 
 impl TextError {
@@ -47,6 +55,7 @@ impl TextError {
             BasicError::InvalidBool => Self::InvalidBool(line),
             BasicError::InvalidPathEncoding => Self::InvalidPathEncoding(line),
             BasicError::NonUnicodeTag => Self::NonUnicodeTag(line),
+            BasicError::DanglingContinuation => Self::DanglingContinuation(line),
         }
     }
 }
@@ -63,6 +72,7 @@ enum ConfigOption {
     FollowMounts(bool),
     FollowLinks(bool),
     BasePath(PathBuf),
+    Include(PathBuf),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -70,16 +80,19 @@ enum TagLine {
     TagStart(String),
     Rule(PathBuf),
     Include(String),
+    FileInclude(PathBuf),
+    Unset(PathBuf),
 }
 
 #[derive(Debug, PartialEq, Eq)]
 struct Line<T> {
+    line: usize,
     inner: Option<T>,
     comment: Option<String>,
 }
 
 impl<T> Line<T> {
-    fn new<S: AsRef<[u8]>>(inner: Option<T>, comment: Option<S>) -> Result<Self, BasicError> {
+    fn new<S: AsRef<[u8]>>(line: usize, inner: Option<T>, comment: Option<S>) -> Result<Self, BasicError> {
         let comment = comment
             .map(|s| {
                 String::from_utf8(s.as_ref().to_owned())
@@ -87,6 +100,7 @@ impl<T> Line<T> {
             .transpose()
             .map_err(|_| BasicError::NonUnicodeComment)?;
         Ok(Self {
+            line,
             inner,
             comment,
         })
@@ -111,6 +125,9 @@ pub enum Rule {
     SymLinkDir(PathBuf, Option<PathBuf>),
     MountPoint(PathBuf),
     Tag(String),
+    /// path (and, like `Suffix`, an optional `a/**/b` suffix) subtracted from this tag's
+    /// rules via `%unset`, resolved after include expansion
+    Unset(PathBuf, Option<PathBuf>),
 }
 
 #[derive(Clone, Default, Debug)]
@@ -165,6 +182,8 @@ fn parse_config(text: &[u8]) -> Result<ConfigOption, BasicError> {
         }
     } else if let Some(path) = text.strip_prefix(b"base-path=") {
         Ok(ConfigOption::BasePath(parse_path(path)?))
+    } else if let Some(path) = text.strip_prefix(b"%include ") {
+        Ok(ConfigOption::Include(parse_path(path)?))
     } else {
         Err(BasicError::InvalidConfigOption)
     };
@@ -182,12 +201,52 @@ fn parse_tag_line(text: &[u8]) -> Result<TagLine, BasicError> {
         }
     } else if text.first() == Some(&b'@') {
         Ok(TagLine::Include(String::from_utf8(text[1..].to_owned()).map_err(|_| BasicError::NonUnicodeTag)?))
+    } else if let Some(path) = text.strip_prefix(b"%include ") {
+        Ok(TagLine::FileInclude(parse_path(path)?))
+    } else if let Some(path) = text.strip_prefix(b"%unset ") {
+        Ok(TagLine::Unset(parse_path(path)?))
     } else {
         parse_path(text).map(TagLine::Rule)
     }
 }
 
-fn parse_text(text: &[u8]) -> Result<ConfigText, TextError> {
+fn is_continuation_line(line: &[u8]) -> bool {
+    match line.first() {
+        Some(b) if b.is_ascii_whitespace() => line.iter().any(|b| !b.is_ascii_whitespace()),
+        _ => false,
+    }
+}
+
+// not blank, a comment, or a tag header
+fn is_foldable(line: &[u8]) -> bool {
+    !matches!(line.first(), None | Some(b'#') | Some(b'['))
+}
+
+// Folds indented continuation lines into the previous logical line, pairing each logical
+// line with the physical line it started on so TextError line numbers stay accurate.
+fn fold_lines(text: &[u8]) -> Result<Vec<(usize, Vec<u8>)>, TextError> {
+    let mut logical: Vec<(usize, Vec<u8>)> = vec![];
+    let mut foldable = false;
+    for (ln, line) in text.split(|x| *x == b'\n').enumerate() {
+        if is_continuation_line(line) {
+            if !foldable {
+                return Err(TextError::new(BasicError::DanglingContinuation, ln))
+            }
+            let trimmed = line
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .map(|start| &line[start..])
+                .unwrap_or(&[]);
+            logical.last_mut().unwrap().1.extend_from_slice(trimmed);
+        } else {
+            foldable = is_foldable(line);
+            logical.push((ln, line.to_vec()));
+        }
+    }
+    Ok(logical)
+}
+
+fn parse_text(text: &[u8], require_version: bool) -> Result<ConfigText, TextError> {
     enum Stage {
         Version,
         Config,
@@ -196,7 +255,8 @@ fn parse_text(text: &[u8]) -> Result<ConfigText, TextError> {
     let mut options = vec![];
     let mut rules = vec![];
     let mut stage = Stage::Version;
-    for (ln, line) in text.split(|x| *x == b'\n').enumerate() {
+    for (ln, line) in fold_lines(text)? {
+        let line = &line[..];
         let (content, comment) = if line.is_empty() {
             (None, None)
         } else if line.first() == Some(&b'#') {
@@ -224,14 +284,29 @@ fn parse_text(text: &[u8]) -> Result<ConfigText, TextError> {
             match stage {
                 Stage::Version => {
                     if let Some(content) = content {
-                        let config = parse_config(content).map_err(ferr)?;
-                        if config != ConfigOption::ConfigVersion0 {
-                            return Err(ferr(BasicError::NotConfigVersion))
+                        match parse_config(content) {
+                            Ok(ConfigOption::ConfigVersion0) => {
+                                stage = Stage::Config;
+                                options.push(Line::new(ln, Some(ConfigOption::ConfigVersion0), comment).map_err(ferr)?);
+                            }
+                            Ok(other) => {
+                                if require_version {
+                                    return Err(ferr(BasicError::NotConfigVersion))
+                                }
+                                // included files may omit the config-version header
+                                stage = Stage::Config;
+                                options.push(Line::new(ln, Some(other), comment).map_err(ferr)?);
+                            }
+                            Err(err) => {
+                                if require_version {
+                                    return Err(ferr(err))
+                                }
+                                stage = Stage::Config;
+                                continue 'goto
+                            }
                         }
-                        stage = Stage::Config;
-                        options.push(Line::new(Some(config), comment).map_err(ferr)?);
                     } else {
-                        options.push(Line::new(None, comment).map_err(ferr)?);
+                        options.push(Line::new(ln, None, comment).map_err(ferr)?);
                     }
                 }
                 Stage::Config => {
@@ -239,10 +314,10 @@ fn parse_text(text: &[u8]) -> Result<ConfigText, TextError> {
                         stage = Stage::Tags;
                         continue 'goto
                     }
-                    options.push(Line::new(content.map(parse_config).transpose().map_err(ferr)?, comment).map_err(ferr)?); 
+                    options.push(Line::new(ln, content.map(parse_config).transpose().map_err(ferr)?, comment).map_err(ferr)?);
                 }
                 Stage::Tags => {
-                    rules.push(Line::new(content.map(parse_tag_line).transpose().map_err(ferr)?, comment).map_err(ferr)?);
+                    rules.push(Line::new(ln, content.map(parse_tag_line).transpose().map_err(ferr)?, comment).map_err(ferr)?);
                 }
             }
             break 'goto
@@ -254,8 +329,25 @@ fn parse_text(text: &[u8]) -> Result<ConfigText, TextError> {
     })
 }
 
-pub fn parse(text: &[u8]) -> Result<Config, Error> {
-    let text = parse_text(text)?;
+// `visited` is the current include chain (not every file ever included), so a shared
+// include reached from two different tags isn't mistaken for a cycle.
+fn resolve_include(base_dir: &Path, rel_path: &Path, line: usize, visited: &mut HashSet<PathBuf>) -> Result<Config, Error> {
+    let full_path = base_dir.join(rel_path);
+    let canonical = fs::canonicalize(&full_path)
+        .map_err(|err| TextError::IncludeNotFound(line + 1, full_path.clone(), err.to_string()))?;
+    if !visited.insert(canonical.clone()) {
+        return Err(TextError::IncludeCycle(line + 1, full_path).into())
+    }
+    let text = fs::read(&canonical)
+        .map_err(|err| TextError::IncludeNotFound(line + 1, full_path.clone(), err.to_string()))?;
+    let included_base_dir = canonical.parent().map(Path::to_path_buf).unwrap_or_default();
+    let result = parse_inner(&text, &included_base_dir, visited, false);
+    visited.remove(&canonical);
+    result
+}
+
+fn parse_inner(text: &[u8], base_dir: &Path, visited: &mut HashSet<PathBuf>, require_version: bool) -> Result<Config, Error> {
+    let text = parse_text(text, require_version)?;
     let mut ret = Config::default();
     for option in text.options {
         match option.inner {
@@ -263,6 +355,10 @@ pub fn parse(text: &[u8]) -> Result<Config, Error> {
             Some(ConfigOption::BasePath(path)) => ret.base_path = path,
             Some(ConfigOption::FollowMounts(val)) => ret.follow_mounts = val,
             Some(ConfigOption::FollowLinks(val)) => ret.follow_links = val,
+            Some(ConfigOption::Include(path)) => {
+                let included = resolve_include(base_dir, &path, option.line, visited)?;
+                ret.tags.extend(included.tags);
+            }
             None => {}
         }
     }
@@ -298,6 +394,17 @@ pub fn parse(text: &[u8]) -> Result<Config, Error> {
                     Rule::Tag(name)
                 });
             }
+            Some(TagLine::FileInclude(path)) => {
+                let included = resolve_include(base_dir, &path, tag_line.line, visited)?;
+                ret.tags.extend(included.tags);
+            }
+            Some(TagLine::Unset(path)) => {
+                tag.rules.push(if let Some((a, b)) = path.as_os_str().to_string_lossy().split_once("/**/") {
+                    Rule::Unset(a.into(), Some(b.into()))
+                } else {
+                    Rule::Unset(path, None)
+                });
+            }
             Some(TagLine::Rule(rule)) => {
                 if let Some((a, b)) = rule.as_os_str().to_string_lossy().split_once("/**/") {
                     tag.rules.push(Rule::Suffix(a.into(), b.into()));
@@ -315,3 +422,13 @@ pub fn parse(text: &[u8]) -> Result<Config, Error> {
     }
     Ok(ret)
 }
+
+// `path` is the file being parsed; %include paths resolve relative to its directory.
+pub fn parse(text: &[u8], path: &Path) -> Result<Config, Error> {
+    let mut visited = HashSet::new();
+    if let Ok(canonical) = fs::canonicalize(path) {
+        visited.insert(canonical);
+    }
+    let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    parse_inner(text, &base_dir, &mut visited, true)
+}
@@ -6,6 +6,25 @@ use std::io::Read;
 use std::path::{Path, PathBuf};
 
 mod config;
+mod query;
+
+#[derive(Debug, Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+    /// Delimit matches with a NUL byte instead of a newline, for piping into e.g. `xargs -0`.
+    #[arg(long, global = true)]
+    print0: bool,
+    /// Output format for each match.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Plain, global = true)]
+    format: OutputFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Plain,
+    Json,
+}
 
 #[derive(Debug, Parser)]
 enum Commands {
@@ -25,6 +44,11 @@ enum Commands {
         second_rule: String,
         other_rules: Vec<String>,
     },
+    /// Boolean expression over tag names, e.g. `(desktop or media) and not cache`.
+    Query {
+        config: PathBuf,
+        expr: String,
+    },
 }
 
 impl Commands {
@@ -33,6 +57,7 @@ impl Commands {
             Self::Or { config, .. } => config,
             Self::Nor { config, .. } => config,
             Self::And { config, .. } => config,
+            Self::Query { config, .. } => config,
         }
     }
     fn rules(&self) -> Vec<String> {
@@ -56,6 +81,8 @@ impl Commands {
                 rules.extend(other_rules.iter().cloned());
                 rules
             }
+            // Query's tags come from the parsed expression, not from flat CLI args.
+            Self::Query { .. } => vec![],
         }
     }
 }
@@ -95,6 +122,107 @@ fn is_symlink_to(path: &Path, target: &Path) -> bool {
     path.read_link().ok().filter(|x| x.starts_with(target)).is_some()
 }
 
+#[cfg(unix)]
+fn is_mount_point(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let Some(parent) = path.parent() else {
+        // no parent: this is the filesystem root, which is trivially a mount point
+        return true
+    };
+    let (Ok(meta), Ok(parent_meta)) = (path.metadata(), parent.metadata()) else {
+        return false
+    };
+    meta.dev() != parent_meta.dev()
+}
+
+#[cfg(not(unix))]
+fn is_mount_point(_path: &Path) -> bool {
+    // no portable way to query a device id outside unix
+    false
+}
+
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn write_nul_terminated(bytes: &[u8]) {
+    use std::io::Write;
+    let stdout = std::io::stdout();
+    let mut lock = stdout.lock();
+    lock.write_all(bytes).unwrap();
+    lock.write_all(b"\0").unwrap();
+}
+
+fn write_path_print0(path: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        write_nul_terminated(path.as_os_str().as_bytes());
+    }
+    #[cfg(not(unix))]
+    {
+        write_nul_terminated(path.to_string_lossy().as_bytes());
+    }
+}
+
+#[cfg(unix)]
+fn path_hex(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(not(unix))]
+fn path_hex(path: &Path) -> String {
+    path.to_string_lossy().as_bytes().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn print_match(path: &Path, tag_names: &[String], file_matches: &[bool], print0: bool, format: OutputFormat) {
+    match format {
+        OutputFormat::Plain => {
+            if print0 {
+                write_path_print0(path);
+            } else {
+                println!("{}", path.display());
+            }
+        }
+        OutputFormat::Json => {
+            let tags = tag_names.iter()
+                .zip(file_matches.iter())
+                .filter(|(_, matched)| **matched)
+                .map(|(tag, _)| json_escape(tag))
+                .collect::<Vec<_>>()
+                .join(",");
+            // `path` is UTF-8 lossy; `path_hex` is the raw bytes, for paths print0 can
+            // round-trip but lossy UTF-8 can't.
+            let line = format!(
+                "{{\"path\":{},\"path_hex\":\"{}\",\"tags\":[{}]}}",
+                json_escape(&path.to_string_lossy()),
+                path_hex(path),
+                tags,
+            );
+            if print0 {
+                write_nul_terminated(line.as_bytes());
+            } else {
+                println!("{line}");
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 enum Rule {
     Plain(String),
@@ -106,62 +234,92 @@ enum Rule {
 }
 
 fn add_rules_to_tree(config: &config::Config, key: &str, rule_name: &str, tree: &mut rule_tree::RulesTree<OsString, Rule>) {
-    let mut rules = vec![rule_name.to_owned()];
-    let mut added = HashSet::new();
-    while let Some(rule) = rules.pop() {
-        if added.contains(&rule) {
+    // %unset applies after the whole @include chain is expanded, so collect it separately
+    // and filter with it afterward rather than excluding rules as they're found.
+    let mut pending = vec![rule_name.to_owned()];
+    let mut visited = HashSet::new();
+    let mut collected = vec![];
+    let mut unset = HashSet::new();
+    while let Some(tag) = pending.pop() {
+        if visited.contains(&tag) {
             continue;
         }
-        added.insert(rule.clone());
-        for rule in &config.tags.get(&rule).unwrap().rules {
+        visited.insert(tag.clone());
+        for rule in &config.tags.get(&tag).unwrap().rules {
             match rule {
-                config::Rule::Suffix(path, sfx) => {
-                    tree.add_rule(path, key, rule_tree::TreeRule::prepend(Rule::Suffix(rule_name.to_owned(), sfx.to_owned())));
-                }
-                config::Rule::Dir(dir) => {
-                    tree.add_rule(dir, key, rule_tree::TreeRule::overwrite(Rule::Plain(rule_name.to_owned())));
-                },
-                config::Rule::File(file) => {
-                    tree.add_rule(file, key, rule_tree::TreeRule::overwrite(Rule::Plain(rule_name.to_owned())));
-                },
-                config::Rule::Exact(name) => {
-                    tree.add_rule(name, key, rule_tree::TreeRule::prepend(Rule::Exact(rule_name.to_owned(), name.clone())));
-                }
-                config::Rule::MountPoint(path) => {
-                    tree.add_rule(path, key, rule_tree::TreeRule::prepend(Rule::MountPoint(rule_name.to_owned())));
-                },
-                config::Rule::SymLink(path, target) => {
-                    tree.add_rule(path, key, rule_tree::TreeRule::prepend(Rule::SymLink(rule_name.to_owned(), target.clone())));
-                }
-                config::Rule::SymLinkDir(path, target) => {
-                    tree.add_rule(path, key, rule_tree::TreeRule::prepend(Rule::SymLinkDir(rule_name.to_owned(), target.clone())));
-                }
-                config::Rule::Tag(name) => {
-                    rules.push(name.to_owned());
+                config::Rule::Tag(name) => pending.push(name.to_owned()),
+                config::Rule::Unset(path, sfx) => {
+                    unset.insert((path, sfx.as_ref()));
                 }
+                other => collected.push(other),
             }
         }
     }
+    for rule in collected {
+        // `%unset path` matches any rule kind keyed on `path`; `%unset path/**/sfx` matches only
+        // the `Suffix` rule with that exact suffix, leaving other suffixes on `path` alone.
+        let is_unset = match rule {
+            config::Rule::Suffix(path, sfx) => unset.contains(&(path, None)) || unset.contains(&(path, Some(sfx))),
+            config::Rule::Dir(path)
+            | config::Rule::File(path)
+            | config::Rule::Exact(path)
+            | config::Rule::MountPoint(path)
+            | config::Rule::SymLink(path, _)
+            | config::Rule::SymLinkDir(path, _) => unset.contains(&(path, None)),
+            config::Rule::Tag(_) | config::Rule::Unset(_, _) => false,
+        };
+        if is_unset {
+            continue;
+        }
+        match rule {
+            config::Rule::Suffix(path, sfx) => {
+                tree.add_rule(path, key, rule_tree::TreeRule::prepend(Rule::Suffix(rule_name.to_owned(), sfx.to_owned())));
+            }
+            config::Rule::Dir(dir) => {
+                tree.add_rule(dir, key, rule_tree::TreeRule::overwrite(Rule::Plain(rule_name.to_owned())));
+            },
+            config::Rule::File(file) => {
+                tree.add_rule(file, key, rule_tree::TreeRule::overwrite(Rule::Plain(rule_name.to_owned())));
+            },
+            config::Rule::Exact(name) => {
+                tree.add_rule(name, key, rule_tree::TreeRule::prepend(Rule::Exact(rule_name.to_owned(), name.clone())));
+            }
+            config::Rule::MountPoint(path) => {
+                tree.add_rule(path, key, rule_tree::TreeRule::prepend(Rule::MountPoint(rule_name.to_owned())));
+            },
+            config::Rule::SymLink(path, target) => {
+                tree.add_rule(path, key, rule_tree::TreeRule::prepend(Rule::SymLink(rule_name.to_owned(), target.clone())));
+            }
+            config::Rule::SymLinkDir(path, target) => {
+                tree.add_rule(path, key, rule_tree::TreeRule::prepend(Rule::SymLinkDir(rule_name.to_owned(), target.clone())));
+            }
+            config::Rule::Tag(_) | config::Rule::Unset(_, _) => unreachable!(),
+        }
+    }
 }
 
 fn main() {
-    let args = Commands::parse();
+    let cli = Cli::parse();
+    let args = &cli.command;
     let conf_path = args.config();
     let mut config = Vec::new();
     let mut file = fs::File::open(conf_path).unwrap();
     file.read_to_end(&mut config).unwrap();
-    let config = config::parse(&config).unwrap();
-    let argrules = args.rules();
-    let walker = walkdir::WalkDir::new(&config.base_path).same_file_system(config.follow_mounts).follow_links(config.follow_links);
+    let config = config::parse(&config, conf_path).unwrap();
+    let query_expr = if let Commands::Query { expr, .. } = &args {
+        Some(query::parse(expr).unwrap())
+    } else {
+        None
+    };
+    let argrules = query_expr.as_ref().map(query::Expr::tag_names).unwrap_or_else(|| args.rules());
+    // `follow_mounts` means "descend into mounted filesystems", i.e. the walker must NOT
+    // restrict itself to the origin filesystem, so mount points below it are actually visited.
+    let walker = walkdir::WalkDir::new(&config.base_path).same_file_system(!config.follow_mounts).follow_links(config.follow_links);
 
     let mut rules = rule_tree::RulesTree::new();
-    let count = if matches!(args, Commands::And { .. }) { argrules.len() } else { 1 };
-    for (i, rule) in argrules.into_iter().enumerate() {
-        if matches!(args, Commands::And { .. }) {
-            add_rules_to_tree(&config, &format!("rule{}", i), &rule, &mut rules);
-        } else {
-            add_rules_to_tree(&config, "rule0", &rule, &mut rules);
-        }
+    let count = argrules.len();
+    for (i, rule) in argrules.iter().enumerate() {
+        add_rules_to_tree(&config, &format!("rule{}", i), rule, &mut rules);
     }
 
     let mut add_rules = vec![];
@@ -202,7 +360,9 @@ fn main() {
                         }
                     }
                     Rule::MountPoint(_) => {
-                        todo!()
+                        if f.file_type().is_dir() && is_mount_point(f.path()) {
+                            m = true;
+                        }
                     }
                 }
             }
@@ -218,9 +378,13 @@ fn main() {
             Commands::Nor { .. } => {
                 matches.iter().all(|x| !x)
             }
+            Commands::Query { .. } => {
+                let values = argrules.iter().cloned().zip(matches.iter().copied()).collect();
+                query_expr.as_ref().unwrap().eval(&values)
+            }
         };
         if m {
-            println!("{}", path.display());
+            print_match(path, &argrules, &matches, cli.print0, cli.format);
         }
     }
 }
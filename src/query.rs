@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// AST for the `query` subcommand's boolean expression language: `tag`, `not`, `and`, `or`
+/// and parentheses over tag names, e.g. `(desktop or media) and not cache`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Tag(String),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+impl Expr {
+    /// Evaluate the expression given each referenced tag's match result.
+    pub fn eval(&self, values: &HashMap<String, bool>) -> bool {
+        match self {
+            Self::Tag(name) => values.get(name).copied().unwrap_or(false),
+            Self::Not(inner) => !inner.eval(values),
+            Self::And(terms) => terms.iter().all(|term| term.eval(values)),
+            Self::Or(terms) => terms.iter().any(|term| term.eval(values)),
+        }
+    }
+
+    /// All distinct tag names referenced by the expression, in order of first appearance.
+    pub fn tag_names(&self) -> Vec<String> {
+        let mut names = vec![];
+        self.collect_tag_names(&mut names);
+        names
+    }
+
+    fn collect_tag_names(&self, names: &mut Vec<String>) {
+        match self {
+            Self::Tag(name) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            Self::Not(inner) => inner.collect_tag_names(names),
+            Self::And(terms) | Self::Or(terms) => {
+                for term in terms {
+                    term.collect_tag_names(names);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum Error {
+    #[error("unexpected end of query expression")]
+    UnexpectedEnd,
+    #[error("unexpected token {0:?} in query expression")]
+    UnexpectedToken(String),
+    #[error("unclosed parenthesis in query expression")]
+    UnclosedParen,
+}
+
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    for c in input.chars() {
+        if c == '(' || c == ')' {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            tokens.push(c.to_string());
+        } else if c.is_whitespace() {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+struct Tokens<'a> {
+    tokens: &'a [String],
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let tok = self.peek();
+        if tok.is_some() {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    // Operator precedence, low to high: or, and, not, atom.
+    fn parse_expr(&mut self) -> Result<Expr, Error> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, Error> {
+        let mut terms = vec![self.parse_and()?];
+        while self.peek().is_some_and(|t| t.eq_ignore_ascii_case("or")) {
+            self.next();
+            terms.push(self.parse_and()?);
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { Expr::Or(terms) })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, Error> {
+        let mut terms = vec![self.parse_not()?];
+        while self.peek().is_some_and(|t| t.eq_ignore_ascii_case("and")) {
+            self.next();
+            terms.push(self.parse_not()?);
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { Expr::And(terms) })
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, Error> {
+        if self.peek().is_some_and(|t| t.eq_ignore_ascii_case("not")) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_not()?)))
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, Error> {
+        match self.next() {
+            Some("(") => {
+                let expr = self.parse_expr()?;
+                match self.next() {
+                    Some(")") => Ok(expr),
+                    _ => Err(Error::UnclosedParen),
+                }
+            }
+            Some(t) if t.eq_ignore_ascii_case("and") || t.eq_ignore_ascii_case("or") || t.eq_ignore_ascii_case("not") || t == ")" => {
+                Err(Error::UnexpectedToken(t.to_owned()))
+            }
+            Some(t) => Ok(Expr::Tag(t.to_owned())),
+            None => Err(Error::UnexpectedEnd),
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Expr, Error> {
+    let tokens = tokenize(input);
+    let mut tokens = Tokens { tokens: &tokens, pos: 0 };
+    let expr = tokens.parse_expr()?;
+    if let Some(t) = tokens.peek() {
+        return Err(Error::UnexpectedToken(t.to_owned()))
+    }
+    Ok(expr)
+}